@@ -2,21 +2,208 @@ use anyhow::Context;
 use anyhow::Result;
 use anyhow::anyhow;
 use anyhow::bail;
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_client::{rpc_client::RpcClient, rpc_config::RpcTransactionConfig};
 use solana_program::instruction::{AccountMeta, Instruction};
+use solana_sdk::address_lookup_table::{
+    AddressLookupTableAccount, state::AddressLookupTable,
+};
 use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::hash::Hash;
+use solana_sdk::message::{VersionedMessage, v0};
 use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
 use solana_sdk::system_instruction;
+use solana_sdk::transaction::VersionedTransaction;
 use solana_sdk::{
     signature::{Keypair, Signer},
     signer::EncodableKey,
     transaction::Transaction,
 };
+use solana_transaction_status::{EncodedTransaction, TransactionBinaryEncoding, UiTransactionEncoding};
+use std::str::FromStr;
 
 #[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
 pub enum MTreeInstruction {
-    InsertLeaf { data: Vec<u8> },
+    InsertLeaf {
+        data: Vec<u8>,
+    },
+    InsertLeaves {
+        data: Vec<Vec<u8>>,
+    },
+    VerifyLeaf {
+        leaf: Vec<u8>,
+        index: u64,
+        proof: Vec<[u8; 32]>,
+    },
+}
+
+/// Mirrors `event::MTreeEvent` on the program side.
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct MTreeEvent {
+    pub root: [u8; 32],
+    pub leaf_index: u64,
+    pub inserted: u64,
+    pub depth: u32,
+}
+
+const MTREE_EVENT_PREFIX: &str = "MTREE_EVENT:";
+
+/// Mirrors `state::MERKLE_DEPTH`/domain tags on the program side, so the
+/// client can fold the same hashes the on-chain tree does.
+const MERKLE_DEPTH: usize = 32;
+const LEAF_DOMAIN_TAG: u8 = 0x00;
+const NODE_DOMAIN_TAG: u8 = 0x01;
+
+fn leaf_hash(data: &[u8]) -> [u8; 32] {
+    let mut tagged = Vec::with_capacity(1 + data.len());
+    tagged.push(LEAF_DOMAIN_TAG);
+    tagged.extend_from_slice(data);
+    solana_program::keccak::hash(&tagged).to_bytes()
+}
+
+fn hash_pair(left: [u8; 32], right: [u8; 32]) -> [u8; 32] {
+    let mut combined = Vec::with_capacity(65);
+    combined.push(NODE_DOMAIN_TAG);
+    combined.extend_from_slice(&left);
+    combined.extend_from_slice(&right);
+    solana_program::keccak::hash(&combined).to_bytes()
+}
+
+fn empty_subtree_roots() -> [[u8; 32]; MERKLE_DEPTH + 1] {
+    let mut empty = [[0u8; 32]; MERKLE_DEPTH + 1];
+    for level in 1..=MERKLE_DEPTH {
+        empty[level] = hash_pair(empty[level - 1], empty[level - 1]);
+    }
+    empty
+}
+
+/// Builds the authentication path for `leaf_index` from the full, ordered
+/// set of leaf hashes, padding with empty-subtree roots beyond
+/// `leaves.len()`. Mirrors `processor::build_proof` on the program side.
+fn build_proof(leaves: &[[u8; 32]], leaf_index: u64) -> Vec<[u8; 32]> {
+    let empty = empty_subtree_roots();
+    let mut level_nodes = leaves.to_vec();
+    let mut index = leaf_index as usize;
+    let mut proof = Vec::with_capacity(MERKLE_DEPTH);
+
+    for level in 0..MERKLE_DEPTH {
+        let sibling = level_nodes.get(index ^ 1).copied().unwrap_or(empty[level]);
+        proof.push(sibling);
+
+        let mut next_level = Vec::with_capacity(level_nodes.len().div_ceil(2));
+        for pair in level_nodes.chunks(2) {
+            let right = pair.get(1).copied().unwrap_or(empty[level]);
+            next_level.push(hash_pair(pair[0], right));
+        }
+        level_nodes = next_level;
+        index /= 2;
+    }
+
+    proof
+}
+
+/// Recomputes the root from `leaf_hash`, `index` and `proof` and checks it
+/// against `root`. Mirrors `processor::verify_proof` on the program side.
+fn verify_proof(root: [u8; 32], leaf_hash: [u8; 32], index: u64, proof: &[[u8; 32]]) -> bool {
+    let mut node = leaf_hash;
+    let mut index = index;
+
+    for sibling in proof {
+        node = if index & 1 == 0 {
+            hash_pair(node, *sibling)
+        } else {
+            hash_pair(*sibling, node)
+        };
+        index >>= 1;
+    }
+
+    node == root
+}
+
+/// Decodes the base64-encoded transaction `encoded` into a
+/// [`VersionedTransaction`], so its instructions can be inspected.
+fn decode_versioned_transaction(encoded: &EncodedTransaction) -> Result<VersionedTransaction> {
+    match encoded {
+        EncodedTransaction::Binary(data, TransactionBinaryEncoding::Base64) => {
+            let bytes = BASE64
+                .decode(data)
+                .with_context(|| "Failed to base64-decode transaction")?;
+            bincode::deserialize(&bytes).with_context(|| "Failed to deserialize transaction")
+        }
+        _ => bail!("Expected a base64-encoded transaction"),
+    }
+}
+
+/// Pulls every leaf `program_id` inserted into `mtree_account`, in on-chain
+/// order, by replaying the account's successful transactions. Only the
+/// signatures returned by one `get_signatures_for_address` call (the RPC's
+/// newest page) are replayed; older history is not fetched. There is no
+/// on-chain proof-generation instruction (an O(n) leaf set as instruction
+/// data would blow past Solana's tx size limit long before the tree got
+/// interesting), so proof building happens here instead, off-chain.
+fn fetch_historical_leaves(
+    client: &RpcClient,
+    program_id: &Pubkey,
+    mtree_account: &Pubkey,
+) -> Result<Vec<Vec<u8>>> {
+    let mut signatures: Vec<Signature> = client
+        .get_signatures_for_address(mtree_account)
+        .with_context(|| "Failed to fetch transaction history for mtree account")?
+        .into_iter()
+        .filter(|status| status.err.is_none())
+        .map(|status| Signature::from_str(&status.signature))
+        .collect::<std::result::Result<_, _>>()
+        .with_context(|| "Failed to parse transaction signature")?;
+    // `get_signatures_for_address` returns newest-first; replay oldest-first
+    // so leaves come back in on-chain insertion order.
+    signatures.reverse();
+
+    let mut leaves = Vec::new();
+    for signature in signatures {
+        let tx = client.get_transaction_with_config(
+            &signature,
+            RpcTransactionConfig {
+                encoding: Some(UiTransactionEncoding::Base64),
+                commitment: Some(CommitmentConfig::confirmed()),
+                max_supported_transaction_version: Some(0),
+            },
+        )?;
+        let versioned = decode_versioned_transaction(&tx.transaction.transaction)?;
+        let account_keys = versioned.message.static_account_keys();
+
+        for instruction in versioned.message.instructions() {
+            if account_keys.get(instruction.program_id_index as usize) != Some(program_id) {
+                continue;
+            }
+            let Ok(decoded) = MTreeInstruction::try_from_slice(&instruction.data) else {
+                continue;
+            };
+            match decoded {
+                MTreeInstruction::InsertLeaf { data } => leaves.push(data),
+                MTreeInstruction::InsertLeaves { data } => leaves.extend(data),
+                MTreeInstruction::VerifyLeaf { .. } => {}
+            }
+        }
+    }
+
+    Ok(leaves)
+}
+
+/// Scans `log_messages` for a line carrying `MTREE_EVENT_PREFIX` and decodes
+/// it, instead of indexing into the logs by position.
+fn find_mtree_event(logs: &[String]) -> Result<MTreeEvent> {
+    let encoded = logs
+        .iter()
+        .find_map(|line| line.strip_prefix(MTREE_EVENT_PREFIX))
+        .ok_or(anyhow!("No MTREE_EVENT log line found"))?;
+
+    let bytes = BASE64
+        .decode(encoded)
+        .with_context(|| "Failed to base64-decode MTREE_EVENT")?;
+    MTreeEvent::try_from_slice(&bytes).with_context(|| "Failed to deserialize MTreeEvent")
 }
 
 fn main() -> Result<()> {
@@ -32,7 +219,9 @@ fn main() -> Result<()> {
 
     // Build transaction
     let leaf_data = get_leaf_data()?;
-    let instruction_data = MTreeInstruction::InsertLeaf { data: leaf_data };
+    let instruction_data = MTreeInstruction::InsertLeaf {
+        data: leaf_data.clone(),
+    };
 
     let insert_leaf_ix = Instruction::new_with_borsh(
         program_id,
@@ -44,14 +233,18 @@ fn main() -> Result<()> {
         }],
     );
 
-    let mut transaction = Transaction::new_with_payer(&[insert_leaf_ix], Some(&payer.pubkey()));
     let recent_blockhash = client
         .get_latest_blockhash()
         .with_context(|| "Failed to get latest blockhash")?;
-    transaction.sign(&[&payer], recent_blockhash);
 
-    // Send and confirm the transaction
-    let signature = client.send_and_confirm_transaction(&transaction)?;
+    let signature = if use_versioned_tx() {
+        send_versioned(&client, &payer, insert_leaf_ix, recent_blockhash)?
+    } else {
+        let mut transaction =
+            Transaction::new_with_payer(&[insert_leaf_ix], Some(&payer.pubkey()));
+        transaction.sign(&[&payer], recent_blockhash);
+        client.send_and_confirm_transaction(&transaction)?
+    };
     println!("Transaction confirmed: {signature}");
 
     // Extract hash from logs
@@ -70,17 +263,112 @@ fn main() -> Result<()> {
         .log_messages
         .ok_or(anyhow!("Log messages are no set"))?;
 
-    let msg_with_root = logs
-        .get(3)
-        .ok_or(anyhow!("Log messages are shorter than expected"))?;
-
-    let root = &msg_with_root[(msg_with_root.len() - 62)..];
+    let event = find_mtree_event(&logs)?;
     println!("===================================================");
-    println!("New root hash: {root}");
+    println!("New root hash: {}", hex::encode(event.root));
+    println!("Leaf index: {}", event.leaf_index);
     println!("===================================================");
+
+    // Rebuild the leaf set off-chain (there is no on-chain proof generator,
+    // see fetch_historical_leaves) and verify our own leaf's inclusion proof
+    // locally instead of just trusting the logged root.
+    let leaves_data = fetch_historical_leaves(&client, &program_id, &mtree_account.pubkey())?;
+    let leaves: Vec<[u8; 32]> = leaves_data.iter().map(|data| leaf_hash(data)).collect();
+    let proof = build_proof(&leaves, event.leaf_index);
+    let leaf = leaf_hash(&leaf_data);
+
+    if !verify_proof(event.root, leaf, event.leaf_index, &proof) {
+        bail!("Locally rebuilt proof does not verify against the on-chain root");
+    }
+    println!("Locally verified inclusion proof for leaf index {}", event.leaf_index);
+
+    // Also have the program check the same proof, so VerifyLeaf itself is
+    // exercised end to end rather than just the client-side math.
+    let verify_leaf_ix = Instruction::new_with_borsh(
+        program_id,
+        &MTreeInstruction::VerifyLeaf {
+            leaf: leaf_data,
+            index: event.leaf_index,
+            proof,
+        },
+        vec![AccountMeta {
+            pubkey: mtree_account.pubkey(),
+            is_signer: false,
+            is_writable: false,
+        }],
+    );
+    let mut verify_tx =
+        Transaction::new_with_payer(&[verify_leaf_ix], Some(&payer.pubkey()));
+    verify_tx.sign(&[&payer], client.get_latest_blockhash()?);
+    let verify_signature = client.send_and_confirm_transaction(&verify_tx)?;
+    println!("On-chain VerifyLeaf confirmed: {verify_signature}");
+
     Ok(())
 }
 
+/// Opt-in switch for versioned (v0) transactions, set via `USE_VERSIONED_TX`.
+/// Defaults to off so existing deployments keep using legacy `Transaction`s.
+fn use_versioned_tx() -> bool {
+    dotenv::var("USE_VERSIONED_TX")
+        .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+/// Builds and sends `instruction` as a v0 transaction, resolving any address
+/// lookup tables named in `ADDRESS_LOOKUP_TABLES` so accounts referenced
+/// only through a table don't have to be listed inline.
+fn send_versioned(
+    client: &RpcClient,
+    payer: &Keypair,
+    instruction: Instruction,
+    recent_blockhash: Hash,
+) -> Result<solana_sdk::signature::Signature> {
+    let lookup_tables = resolve_lookup_tables(client)?;
+
+    let message = v0::Message::try_compile(
+        &payer.pubkey(),
+        &[instruction],
+        &lookup_tables,
+        recent_blockhash,
+    )
+    .with_context(|| "Failed to compile v0 message")?;
+
+    let transaction = VersionedTransaction::try_new(VersionedMessage::V0(message), &[payer])
+        .with_context(|| "Failed to sign versioned transaction")?;
+
+    client
+        .send_and_confirm_transaction(&transaction)
+        .with_context(|| "Failed to send versioned transaction")
+}
+
+/// Reads a comma-separated list of address lookup table pubkeys from
+/// `ADDRESS_LOOKUP_TABLES` and fetches each one, returning an empty list if
+/// the var is unset.
+fn resolve_lookup_tables(client: &RpcClient) -> Result<Vec<AddressLookupTableAccount>> {
+    let Ok(addresses) = dotenv::var("ADDRESS_LOOKUP_TABLES") else {
+        return Ok(Vec::new());
+    };
+
+    addresses
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|address| {
+            let key = Pubkey::from_str(address)
+                .with_context(|| format!("Invalid lookup table address: {address}"))?;
+            let account = client
+                .get_account(&key)
+                .with_context(|| format!("Failed to fetch lookup table account {key}"))?;
+            let table = AddressLookupTable::deserialize(&account.data)
+                .with_context(|| format!("Failed to parse lookup table account {key}"))?;
+            Ok(AddressLookupTableAccount {
+                key,
+                addresses: table.addresses.to_vec(),
+            })
+        })
+        .collect()
+}
+
 fn get_leaf_data() -> Result<Vec<u8>> {
     let args: Vec<String> = std::env::args().collect();
     if args.len() != 2 {
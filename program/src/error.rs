@@ -0,0 +1,41 @@
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+use solana_program::{
+    decode_error::DecodeError,
+    msg,
+    program_error::{PrintProgramError, ProgramError},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug, Copy, Clone, PartialEq, Eq, FromPrimitive)]
+pub enum MTreeError {
+    #[error("A leaf proof does not verify against the stored root")]
+    ProofVerificationFailed,
+
+    #[error("Account holds an MTree of an unsupported version")]
+    UnsupportedTreeVersion,
+
+    #[error("InsertLeaves requires at least one leaf")]
+    EmptyLeafBatch,
+}
+
+impl From<MTreeError> for ProgramError {
+    fn from(e: MTreeError) -> Self {
+        ProgramError::Custom(e as u32)
+    }
+}
+
+impl<T> DecodeError<T> for MTreeError {
+    fn type_of() -> &'static str {
+        "MTreeError"
+    }
+}
+
+impl PrintProgramError for MTreeError {
+    fn print<E>(&self)
+    where
+        E: 'static + std::error::Error + DecodeError<E> + PrintProgramError + FromPrimitive,
+    {
+        msg!("MTreeError: {}", self);
+    }
+}
@@ -0,0 +1,27 @@
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::msg;
+
+/// Log line prefix that precedes a base64-encoded, Borsh-serialized
+/// [`MTreeEvent`]. Scanning for this is a stable alternative to indexing
+/// into `log_messages` by position.
+pub const MTREE_EVENT_PREFIX: &str = "MTREE_EVENT:";
+
+#[derive(Clone, Debug, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct MTreeEvent {
+    pub root: [u8; 32],
+    /// Index of the first leaf inserted by this call.
+    pub leaf_index: u64,
+    /// Number of leaves inserted by this call (1 for `InsertLeaf`, the batch
+    /// size for `InsertLeaves`), so a consumer doesn't have to guess whether
+    /// the whole batch landed from `leaf_index` alone.
+    pub inserted: u64,
+    pub depth: u32,
+}
+
+/// Emits `event` as a single `msg!` line under [`MTREE_EVENT_PREFIX`].
+pub fn emit(event: &MTreeEvent) {
+    let encoded = BASE64.encode(borsh::to_vec(event).expect("MTreeEvent always serializes"));
+    msg!("{}{}", MTREE_EVENT_PREFIX, encoded);
+}
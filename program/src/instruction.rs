@@ -2,5 +2,28 @@ use borsh::{BorshDeserialize, BorshSerialize};
 
 #[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
 pub enum MTreeInstruction {
-    InsertLeaf { data: Vec<u8> },
+    InsertLeaf {
+        data: Vec<u8>,
+    },
+    /// Appends every item in `data` as a leaf and recomputes `root` once,
+    /// instead of once per leaf. The common ingestion pattern for airdrop
+    /// and allowlist trees, where a single transaction needs to land many
+    /// leaves at once.
+    InsertLeaves {
+        data: Vec<Vec<u8>>,
+    },
+    /// Recomputes the root from `leaf` and `proof` and checks it against the
+    /// tree's stored root. Proof *generation* happens entirely off-chain
+    /// (the caller rebuilds its leaf set by replaying the insert instructions
+    /// sent to the tree account and derives the authentication path
+    /// locally): an on-chain `GenerateProof` would
+    /// need the full, O(n) leaf set as instruction data, which blows past
+    /// Solana's ~1232-byte transaction limit at a few dozen leaves and
+    /// can't scale to the airdrop/allowlist trees this program targets.
+    /// `VerifyLeaf` only needs the O(log n) proof, so it stays on-chain.
+    VerifyLeaf {
+        leaf: Vec<u8>,
+        index: u64,
+        proof: Vec<[u8; 32]>,
+    },
 }
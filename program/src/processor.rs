@@ -8,7 +8,12 @@ use solana_program::{
     pubkey::Pubkey,
 };
 
-use crate::{instruction::MTreeInstruction, state::MTree};
+use crate::{
+    error::MTreeError,
+    event::{MTreeEvent, emit},
+    instruction::MTreeInstruction,
+    state::{LEAF_DOMAIN_TAG, MERKLE_DEPTH, MTREE_VERSION, MTree, NODE_DOMAIN_TAG, empty_subtree_roots},
+};
 
 pub struct Processor;
 impl Processor {
@@ -23,6 +28,14 @@ impl Processor {
             MTreeInstruction::InsertLeaf { data } => {
                 Self::process_insert_leaf(program_id, accounts, data)?
             }
+            MTreeInstruction::InsertLeaves { data } => {
+                Self::process_insert_leaves(accounts, data)?
+            }
+            MTreeInstruction::VerifyLeaf {
+                leaf,
+                index,
+                proof,
+            } => Self::process_verify_leaf(accounts, leaf, index, proof)?,
         }
 
         Ok(())
@@ -36,22 +49,53 @@ impl Processor {
         let signer = next_account_info(&mut accounts.iter())?;
         msg!("Signer: {}", signer.key);
 
-        let mut tree = if let Ok(tree) = MTree::try_from_slice(&signer.data.borrow()) {
-            tree
-        } else {
-            MTree {
-                root: [0; 32],
-                leaves: Vec::new(),
-            }
-        };
+        let mut tree = decode_tree_or_default(&signer.data.borrow())?;
         msg!("MTree decoded");
 
         insert_leaf(&mut tree, data.as_slice());
         msg!("Root hash updated: {}", hex::encode(tree.root));
         msg!("Depth: {}", depth(&tree));
+        emit(&MTreeEvent {
+            root: tree.root,
+            leaf_index: tree.leaf_count - 1,
+            inserted: 1,
+            depth: MERKLE_DEPTH as u32,
+        });
+
+        Self::save_tree(signer, &tree)
+    }
+
+    fn process_insert_leaves(accounts: &[AccountInfo], data: Vec<Vec<u8>>) -> ProgramResult {
+        if data.is_empty() {
+            return Err(MTreeError::EmptyLeafBatch.into());
+        }
 
-        // Serialize the updated state back to the account
-        let serialized_data = borsh::to_vec(&tree)?;
+        let signer = next_account_info(&mut accounts.iter())?;
+        msg!("Signer: {}", signer.key);
+
+        let mut tree = decode_tree_or_default(&signer.data.borrow())?;
+        msg!("MTree decoded");
+
+        for leaf_data in &data {
+            append_leaf(&mut tree, leaf_data);
+        }
+        tree.root = fold_root(&tree);
+
+        msg!("Inserted {} leaves", data.len());
+        msg!("Root hash updated: {}", hex::encode(tree.root));
+        msg!("Leaf count: {}", tree.leaf_count);
+        emit(&MTreeEvent {
+            root: tree.root,
+            leaf_index: tree.leaf_count - data.len() as u64,
+            inserted: data.len() as u64,
+            depth: MERKLE_DEPTH as u32,
+        });
+
+        Self::save_tree(signer, &tree)
+    }
+
+    fn save_tree(signer: &AccountInfo, tree: &MTree) -> ProgramResult {
+        let serialized_data = borsh::to_vec(tree)?;
 
         // Ensure the account has enough space
         if serialized_data.len() > signer.data_len() {
@@ -64,56 +108,171 @@ impl Processor {
         }
 
         // Save the updated merkle tree back to the account
-
         let mut data = signer.data.borrow_mut();
         data[..serialized_data.len()].copy_from_slice(&serialized_data);
         msg!("MTree updated");
 
         Ok(())
     }
+
+    fn process_verify_leaf(
+        accounts: &[AccountInfo],
+        leaf: Vec<u8>,
+        index: u64,
+        proof: Vec<[u8; 32]>,
+    ) -> ProgramResult {
+        let signer = next_account_info(&mut accounts.iter())?;
+        let tree = decode_tree_or_default(&signer.data.borrow())?;
+
+        let leaf = leaf_hash(&leaf);
+        if !verify_proof(tree.root, leaf, index, &proof) {
+            return Err(MTreeError::ProofVerificationFailed.into());
+        }
+
+        msg!("Leaf at index {} verified against root", index);
+
+        Ok(())
+    }
 }
 
-pub fn depth(tree: &MTree) -> usize {
-    let leaf_count = tree.leaves.len();
-    if leaf_count == 0 {
-        return 0;
+pub fn depth(_tree: &MTree) -> usize {
+    MERKLE_DEPTH
+}
+
+/// Decodes `MTree` from account data, treating an all-zero (freshly
+/// allocated) or undecodable account as a brand new tree but rejecting a
+/// decodable account of an unsupported version rather than silently
+/// overwriting it.
+///
+/// Deserializes from the account's initialized prefix rather than
+/// `try_from_slice`-ing the whole fixed-size buffer: the account is always
+/// allocated larger than the serialized tree, and `try_from_slice` errors on
+/// any unconsumed trailing bytes, which would otherwise make every decode
+/// after the first discard the previously-stored frontier.
+fn decode_tree_or_default(data: &[u8]) -> Result<MTree, ProgramError> {
+    if data.iter().all(|&byte| byte == 0) {
+        return Ok(MTree::empty());
     }
-    let mut depth = 0;
-    let mut nodes = leaf_count;
-    while nodes > 1 {
-        nodes = nodes.div_ceil(2);
-        depth += 1;
+    match MTree::deserialize(&mut &data[..]) {
+        Ok(tree) if tree.version == MTREE_VERSION => Ok(tree),
+        Ok(_) => Err(MTreeError::UnsupportedTreeVersion.into()),
+        Err(_) => Ok(MTree::empty()),
     }
-    depth
 }
 
-fn insert_leaf(tree: &mut MTree, data: &[u8]) {
-    let data_hash = hash(data).to_bytes();
-    tree.leaves.push(data_hash);
-
-    // Recalculate root
+/// Domain-separated hash of a leaf's raw data, so a leaf can never be
+/// replayed as an internal node hash.
+fn leaf_hash(data: &[u8]) -> [u8; 32] {
+    let mut tagged = Vec::with_capacity(1 + data.len());
+    tagged.push(LEAF_DOMAIN_TAG);
+    tagged.extend_from_slice(data);
+    hash(&tagged).to_bytes()
+}
 
-    let mut current_level = tree.leaves.clone();
+/// Domain-separated hash of two child nodes.
+fn hash_pair(left: [u8; 32], right: [u8; 32]) -> [u8; 32] {
+    let mut combined = Vec::with_capacity(65);
+    combined.push(NODE_DOMAIN_TAG);
+    combined.extend_from_slice(&left);
+    combined.extend_from_slice(&right);
+    hash(&combined).to_bytes()
+}
 
-    while current_level.len() > 1 {
-        let mut next_level = Vec::new();
+/// Appends `data` to the frontier without deriving `root`. Used so batch
+/// inserts can update the frontier for every leaf and fold the root only
+/// once at the end.
+fn append_leaf(tree: &mut MTree, data: &[u8]) {
+    let mut node = leaf_hash(data);
+    let index = tree.leaf_count;
 
-        for i in (0..current_level.len()).step_by(2) {
-            // If only one left, move leaf to upper level
-            if i + 1 < current_level.len() {
-                let mut combined = Vec::with_capacity(64);
-                combined.extend_from_slice(&current_level[i]);
-                combined.extend_from_slice(&current_level[i + 1]);
-                let parent = hash(&combined).to_bytes();
-                next_level.push(parent);
+    for level in 0..MERKLE_DEPTH {
+        if (index >> level) & 1 == 1 {
+            node = hash_pair(tree.frontier[level], node);
+        } else {
+            if level == tree.frontier.len() {
+                tree.frontier.push(node);
             } else {
-                next_level.push(current_level[i]);
+                tree.frontier[level] = node;
             }
+            break;
         }
-        current_level = next_level;
     }
 
-    tree.root = current_level[0];
+    tree.leaf_count += 1;
+}
+
+/// Derives `root` from `frontier` and `leaf_count` by folding the frontier
+/// from the bottom, padding with precomputed empty-subtree roots wherever
+/// a level has nothing inserted under it.
+fn fold_root(tree: &MTree) -> [u8; 32] {
+    let empty = empty_subtree_roots();
+    let mut acc: Option<[u8; 32]> = None;
+
+    for level in 0..MERKLE_DEPTH {
+        if (tree.leaf_count >> level) & 1 == 1 {
+            let right = acc.unwrap_or(empty[level]);
+            acc = Some(hash_pair(tree.frontier[level], right));
+        } else if let Some(left) = acc {
+            acc = Some(hash_pair(left, empty[level]));
+        }
+    }
+
+    acc.unwrap_or(empty[MERKLE_DEPTH])
+}
+
+fn insert_leaf(tree: &mut MTree, data: &[u8]) {
+    append_leaf(tree, data);
+    tree.root = fold_root(tree);
+}
+
+/// Builds the authentication path for `leaf_index` from a full, ordered set
+/// of leaf hashes, padding with empty-subtree roots beyond `leaves.len()`.
+///
+/// Only used by tests to exercise [`verify_proof`]: real proofs are built
+/// off-chain by the caller (see `MTreeInstruction::VerifyLeaf`), since an
+/// on-chain equivalent would need the full leaf set as instruction data.
+#[cfg(test)]
+fn build_proof(leaves: &[[u8; 32]], leaf_index: u64) -> Vec<[u8; 32]> {
+    let empty = empty_subtree_roots();
+    let mut level_nodes = leaves.to_vec();
+    let mut index = leaf_index as usize;
+    let mut proof = Vec::with_capacity(MERKLE_DEPTH);
+
+    for level in 0..MERKLE_DEPTH {
+        let sibling = level_nodes
+            .get(index ^ 1)
+            .copied()
+            .unwrap_or(empty[level]);
+        proof.push(sibling);
+
+        let mut next_level = Vec::with_capacity(level_nodes.len().div_ceil(2));
+        for pair in level_nodes.chunks(2) {
+            let right = pair.get(1).copied().unwrap_or(empty[level]);
+            next_level.push(hash_pair(pair[0], right));
+        }
+        level_nodes = next_level;
+        index /= 2;
+    }
+
+    proof
+}
+
+/// Recomputes the root from `leaf_hash`, `index` and `proof` and checks it
+/// against `root`.
+pub fn verify_proof(root: [u8; 32], leaf_hash: [u8; 32], index: u64, proof: &[[u8; 32]]) -> bool {
+    let mut node = leaf_hash;
+    let mut index = index;
+
+    for sibling in proof {
+        node = if index & 1 == 0 {
+            hash_pair(node, *sibling)
+        } else {
+            hash_pair(*sibling, node)
+        };
+        index >>= 1;
+    }
+
+    node == root
 }
 
 // Tests for the Merkle Tree Solana Program
@@ -149,33 +308,29 @@ mod tests {
 
     #[test]
     fn test_insert_leaf() {
-        // Create a new merkle tree
-        let mut tree = MTree {
-            root: [0; 32],
-            leaves: Vec::new(),
-        };
+        let mut tree = MTree::empty();
 
         // Insert a leaf
         let leaf_data = b"Test leaf";
         insert_leaf(&mut tree, leaf_data.as_slice());
 
-        // Check that the leaf was added
-        assert_eq!(tree.leaves.len(), 1);
+        assert_eq!(tree.leaf_count, 1);
+
+        // With a single leaf, the root is that leaf paired with empty
+        // subtrees all the way up.
+        let empty = empty_subtree_roots();
+        let mut expected = leaf_hash(leaf_data);
+        for level in 0..MERKLE_DEPTH {
+            expected = hash_pair(expected, empty[level]);
+        }
 
-        // Verify that the root is updated
-        let expected_leaf_hash = hash(leaf_data).to_bytes();
-        assert_eq!(tree.root, expected_leaf_hash);
+        assert_eq!(tree.root, expected);
     }
 
     #[test]
     fn test_multiple_leaves() {
-        // Create a new merkle tree
-        let mut tree = MTree {
-            root: [0; 32],
-            leaves: Vec::new(),
-        };
+        let mut tree = MTree::empty();
 
-        // Insert multiple leaves
         let leaf1 = b"Leaf 1";
         let leaf2 = b"Leaf 2";
 
@@ -187,17 +342,19 @@ mod tests {
 
         // Ensure root changes after adding second leaf
         assert_ne!(root_after_one, root_after_two);
-        assert_eq!(tree.leaves.len(), 2);
+        assert_eq!(tree.leaf_count, 2);
 
-        let hash1 = hash(leaf1).to_bytes();
-        let hash2 = hash(leaf2).to_bytes();
+        let hash1 = leaf_hash(leaf1);
+        let hash2 = leaf_hash(leaf2);
 
-        let mut combined = Vec::with_capacity(64);
-        combined.extend_from_slice(&hash1);
-        combined.extend_from_slice(&hash2);
-        let expected_root = hash(&combined).to_bytes();
+        let mut expected = hash_pair(hash1, hash2);
 
-        assert_eq!(tree.root, expected_root);
+        let empty = empty_subtree_roots();
+        for level in 1..MERKLE_DEPTH {
+            expected = hash_pair(expected, empty[level]);
+        }
+
+        assert_eq!(tree.root, expected);
     }
 
     #[test]
@@ -238,57 +395,184 @@ mod tests {
 
         // Check the result
         assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_root_deterministic_for_leaf_count() {
+        // Inserting the same three leaves into two independent trees should
+        // always converge on the same root, regardless of the old "carry the
+        // odd leaf up unchanged" special-casing this replaces.
+        let mut tree_a = MTree::empty();
+        let mut tree_b = MTree::empty();
+
+        let leaves: [&[u8]; 3] = [b"Leaf 1", b"Leaf 2", b"Leaf 3"];
+        for leaf in leaves {
+            insert_leaf(&mut tree_a, leaf);
+            insert_leaf(&mut tree_b, leaf);
+        }
 
-        // // Deserialize the account data to check if the leaf was inserted
-        // dbg!(accounts[0].data.borrow().len());
-        // let merkle_tree = MTree::try_from_slice(&accounts[0].data.borrow()).unwrap();
+        assert_eq!(tree_a.root, tree_b.root);
+        assert_eq!(tree_a.leaf_count, 3);
+    }
 
-        // // Check that the leaf was inserted
-        // assert_eq!(merkle_tree.leaves.len(), 1);
+    #[test]
+    fn test_insert_leaves_matches_sequential_inserts() {
+        let leaves: [&[u8]; 4] = [b"Leaf 1", b"Leaf 2", b"Leaf 3", b"Leaf 4"];
+
+        let mut sequential = MTree::empty();
+        for leaf in leaves {
+            insert_leaf(&mut sequential, leaf);
+        }
 
-        // // Check that the root was updated
-        // let expected_leaf_hash = hash(leaf_data).to_bytes();
-        // assert_eq!(merkle_tree.root, expected_leaf_hash);
+        let mut batched = MTree::empty();
+        for leaf in &leaves {
+            append_leaf(&mut batched, leaf);
+        }
+        batched.root = fold_root(&batched);
+
+        assert_eq!(batched.root, sequential.root);
+        assert_eq!(batched.leaf_count, sequential.leaf_count);
     }
 
     #[test]
-    fn test_tree_with_odd_number_of_leaves() {
-        // Create a new merkle tree
-        let mut tree = MTree {
-            root: [0; 32],
-            leaves: Vec::new(),
-        };
+    fn test_insert_leaves_rejects_empty_batch() {
+        let program_id = Pubkey::new_unique();
+        let merkle_pubkey = Keypair::new().pubkey();
 
-        // Insert three leaves
-        let leaf1 = b"Leaf 1";
-        let leaf2 = b"Leaf 2";
-        let leaf3 = b"Leaf 3";
+        let mut lamports = 100000;
+        let mut data = vec![0; 10000];
 
-        insert_leaf(&mut tree, leaf1.as_slice());
-        insert_leaf(&mut tree, leaf2.as_slice());
-        insert_leaf(&mut tree, leaf3.as_slice());
-
-        // Check that all leaves were added
-        assert_eq!(tree.leaves.len(), 3);
-
-        // Manually calculate what the root should be with 3 leaves
-        let hash1 = hash(leaf1).to_bytes();
-        let hash2 = hash(leaf2).to_bytes();
-        let hash3 = hash(leaf3).to_bytes();
-
-        // First combine hash1 and hash2
-        let mut combined = Vec::with_capacity(64);
-        combined.extend_from_slice(&hash1);
-        combined.extend_from_slice(&hash2);
-        let parent1 = hash(&combined).to_bytes();
-
-        // Then combine parent1 with hash3
-        let mut combined = Vec::with_capacity(64);
-        combined.extend_from_slice(&parent1);
-        combined.extend_from_slice(&hash3);
-        let expected_root = hash(&combined).to_bytes();
-
-        // Verify the result
-        assert_eq!(tree.root, expected_root);
+        let merkle_account = create_account_info(
+            &merkle_pubkey,
+            false,
+            true,
+            Rc::new(RefCell::new(&mut lamports)),
+            Rc::new(RefCell::new(&mut data)),
+            &program_id,
+        );
+        let accounts = vec![merkle_account];
+
+        let instruction = MTreeInstruction::InsertLeaves { data: Vec::new() };
+        let instruction_data = borsh::to_vec(&instruction).unwrap();
+
+        let result = process_instruction(&program_id, &accounts, &instruction_data);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_proof_round_trip() {
+        let mut tree = MTree::empty();
+        let leaves_data: [&[u8]; 4] = [b"Leaf 1", b"Leaf 2", b"Leaf 3", b"Leaf 4"];
+        for leaf in leaves_data {
+            insert_leaf(&mut tree, leaf);
+        }
+
+        let leaves: Vec<[u8; 32]> = leaves_data.iter().map(|l| leaf_hash(l)).collect();
+
+        for (index, leaf_hash) in leaves.iter().enumerate() {
+            let proof = build_proof(&leaves, index as u64);
+            assert!(verify_proof(tree.root, *leaf_hash, index as u64, &proof));
+        }
+    }
+
+    #[test]
+    fn test_proof_rejects_wrong_leaf() {
+        let mut tree = MTree::empty();
+        let leaves_data: [&[u8]; 2] = [b"Leaf 1", b"Leaf 2"];
+        for leaf in leaves_data {
+            insert_leaf(&mut tree, leaf);
+        }
+
+        let leaves: Vec<[u8; 32]> = leaves_data.iter().map(|l| leaf_hash(l)).collect();
+        let proof = build_proof(&leaves, 0);
+
+        let wrong_leaf = leaf_hash(b"Not a leaf");
+        assert!(!verify_proof(tree.root, wrong_leaf, 0, &proof));
+    }
+
+    #[test]
+    fn test_account_accumulates_across_separate_calls() {
+        let program_id = Pubkey::new_unique();
+        let merkle_pubkey = Keypair::new().pubkey();
+
+        let mut lamports = 100000;
+        let mut data = vec![0; 10000];
+
+        {
+            let merkle_account = create_account_info(
+                &merkle_pubkey,
+                false,
+                true,
+                Rc::new(RefCell::new(&mut lamports)),
+                Rc::new(RefCell::new(&mut data)),
+                &program_id,
+            );
+            let accounts = vec![merkle_account];
+
+            let instruction = MTreeInstruction::InsertLeaf {
+                data: b"Leaf 1".to_vec(),
+            };
+            let instruction_data = borsh::to_vec(&instruction).unwrap();
+            process_instruction(&program_id, &accounts, &instruction_data).unwrap();
+        }
+
+        {
+            let merkle_account = create_account_info(
+                &merkle_pubkey,
+                false,
+                true,
+                Rc::new(RefCell::new(&mut lamports)),
+                Rc::new(RefCell::new(&mut data)),
+                &program_id,
+            );
+            let accounts = vec![merkle_account];
+
+            let instruction = MTreeInstruction::InsertLeaf {
+                data: b"Leaf 2".to_vec(),
+            };
+            let instruction_data = borsh::to_vec(&instruction).unwrap();
+            process_instruction(&program_id, &accounts, &instruction_data).unwrap();
+        }
+
+        let tree = decode_tree_or_default(&data).unwrap();
+
+        let mut expected = MTree::empty();
+        insert_leaf(&mut expected, b"Leaf 1");
+        insert_leaf(&mut expected, b"Leaf 2");
+
+        assert_eq!(tree.leaf_count, 2);
+        assert_eq!(tree.root, expected.root);
+    }
+
+    #[test]
+    fn test_insert_leaf_rejects_unsupported_version() {
+        let program_id = Pubkey::new_unique();
+        let merkle_pubkey = Keypair::new().pubkey();
+
+        let mut tree = MTree::empty();
+        tree.version = MTREE_VERSION + 1;
+        let serialized = borsh::to_vec(&tree).unwrap();
+        let mut data = vec![0; 10000];
+        data[..serialized.len()].copy_from_slice(&serialized);
+
+        let mut lamports = 100000;
+
+        let merkle_account = create_account_info(
+            &merkle_pubkey,
+            false,
+            true,
+            Rc::new(RefCell::new(&mut lamports)),
+            Rc::new(RefCell::new(&mut data)),
+            &program_id,
+        );
+        let accounts = vec![merkle_account];
+
+        let instruction = MTreeInstruction::InsertLeaf {
+            data: b"Leaf".to_vec(),
+        };
+        let instruction_data = borsh::to_vec(&instruction).unwrap();
+
+        let result = process_instruction(&program_id, &accounts, &instruction_data);
+        assert!(result.is_err());
     }
 }
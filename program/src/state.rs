@@ -1,7 +1,63 @@
 use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::keccak::hash;
 
+/// Fixed depth of the on-chain tree. 32 levels comfortably covers any leaf
+/// count we will ever store in a 10000-byte account, and gives the table of
+/// empty-subtree roots a fixed size. `empty_subtree_roots()` rebuilds that
+/// table each time it is called.
+pub const MERKLE_DEPTH: usize = 32;
+
+/// Current on-disk layout of [`MTree`]. Bump this whenever the hashing
+/// scheme or field layout changes so old accounts are rejected instead of
+/// silently producing incompatible roots.
+pub const MTREE_VERSION: u8 = 1;
+
+/// Domain tag prepended to leaf data before hashing, so a leaf hash can
+/// never be replayed as an internal node hash (second-preimage resistance).
+pub const LEAF_DOMAIN_TAG: u8 = 0x00;
+
+/// Domain tag prepended to a pair of child hashes before hashing.
+pub const NODE_DOMAIN_TAG: u8 = 0x01;
+
+/// An append-only incremental Merkle tree.
+///
+/// Rather than keeping every leaf (which makes each insert an O(n) rebuild
+/// and grows the account without bound), we keep only the *frontier*: for
+/// each level, the most recently completed left-hand node that is still
+/// waiting for a sibling. `root` is stored alongside them and re-derived from
+/// `frontier` and `leaf_count` after each insert (once per batch) by folding
+/// in empty-subtree roots for the levels that have nothing inserted under
+/// them yet.
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
 pub struct MTree {
+    pub version: u8,
     pub root: [u8; 32],
-    pub leaves: Vec<[u8; 32]>,
+    pub leaf_count: u64,
+    pub frontier: Vec<[u8; 32]>,
+}
+
+impl MTree {
+    pub fn empty() -> Self {
+        MTree {
+            version: MTREE_VERSION,
+            root: empty_subtree_roots()[MERKLE_DEPTH],
+            leaf_count: 0,
+            frontier: Vec::new(),
+        }
+    }
+}
+
+/// `empty[0]` is the all-zero leaf placeholder; `empty[level]` is the root
+/// of a perfectly empty subtree of that level, i.e. the domain-separated
+/// hash of `empty[level-1]` with itself.
+pub fn empty_subtree_roots() -> [[u8; 32]; MERKLE_DEPTH + 1] {
+    let mut empty = [[0u8; 32]; MERKLE_DEPTH + 1];
+    for level in 1..=MERKLE_DEPTH {
+        let mut combined = Vec::with_capacity(65);
+        combined.push(NODE_DOMAIN_TAG);
+        combined.extend_from_slice(&empty[level - 1]);
+        combined.extend_from_slice(&empty[level - 1]);
+        empty[level] = hash(&combined).to_bytes();
+    }
+    empty
 }